@@ -1,6 +1,7 @@
 mod trace;
 mod decode;
 mod decompile;
+mod serve;
 
 use clap::{Parser, Subcommand};
 
@@ -8,6 +9,7 @@ use heimdall_config::{config, get_config, ConfigArgs};
 use heimdall_common::ether::evm::disassemble::*;
 use decode::{decode, DecodeArgs};
 use trace::{trace, TraceArgs};
+use serve::{serve, ServeArgs};
 
 
 #[derive(Debug, Parser)]
@@ -40,6 +42,9 @@ pub enum Subcommands {
 
     #[clap(name = "trace", about = "Trace the execution of a transaction hash")]
     Trace(TraceArgs),
+
+    #[clap(name = "serve", about = "Start a long-running RPC server exposing heimdall's analysis tools")]
+    Serve(ServeArgs),
 }
 
 fn main() {
@@ -87,6 +92,18 @@ fn main() {
 
             trace(cmd)
         }
-        
+
+        Subcommands::Serve(mut cmd) => {
+            // if the user has not specified a rpc url, use the default
+            match cmd.rpc_url.as_str() {
+                "" => {
+                    cmd.rpc_url = configuration.rpc_url.clone();
+                }
+                _ => {}
+            };
+
+            serve(cmd);
+        }
+
     }
 }
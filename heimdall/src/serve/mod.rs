@@ -0,0 +1,127 @@
+use std::{str::FromStr, sync::Arc};
+use clap::{AppSettings, Parser};
+use ethers::{
+    prelude::{Provider, Http, Middleware},
+    types::H256,
+};
+use jsonrpsee::{
+    core::Error as RpcError,
+    http_server::{HttpServerBuilder, RpcModule},
+};
+use heimdall_common::io::logging::Logger;
+
+use crate::decode::decode_calldata_with_provider;
+
+
+#[derive(Debug, Clone, Parser)]
+#[clap(about = "Start a long-running RPC server exposing heimdall's analysis tools",
+       after_help = "For more information, read the wiki: https://jbecker.dev/r/heimdall-rs/wiki",
+       global_setting = AppSettings::DeriveDisplayOrder,
+       override_usage = "heimdall serve [OPTIONS]")]
+pub struct ServeArgs {
+
+    /// The port to listen for JSON-RPC requests on.
+    #[clap(long, short, default_value = "8045")]
+    pub port: u16,
+
+    /// The RPC provider to use for fetching target bytecode and traces.
+    #[clap(long="rpc-url", short, default_value = "", hide_default_value = true)]
+    pub rpc_url: String,
+
+    /// Set the output verbosity level, 1 - 5.
+    #[clap(flatten)]
+    pub verbose: clap_verbosity_flag::Verbosity,
+
+}
+
+/// Start the `heimdall serve` JSON-RPC server.
+///
+/// Unlike the other subcommands, which build a new runtime and RPC provider
+/// on every invocation, this keeps a single `Provider<Http>` alive for the
+/// lifetime of the process and answers requests against it, so callers that
+/// want to integrate heimdall programmatically don't pay reconnection cost
+/// on every call.
+#[allow(deprecated)]
+pub fn serve(args: ServeArgs) {
+    let (logger, _) = Logger::new(args.verbose.log_level().unwrap().as_str());
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+
+    rt.block_on(async {
+
+        // make sure the RPC provider isn't empty
+        if &args.rpc_url.len() <= &0 {
+            logger.error("serving requires an RPC provider. Use `heimdall serve --help` for more information.");
+            std::process::exit(1);
+        }
+
+        // create the single, long-lived provider every method below shares
+        let provider = match Provider::<Http>::try_from(&args.rpc_url) {
+            Ok(provider) => Arc::new(provider),
+            Err(_) => {
+                logger.error(&format!("failed to connect to RPC provider '{}' .", &args.rpc_url));
+                std::process::exit(1)
+            }
+        };
+
+        let mut module = RpcModule::new(provider);
+
+        module
+            .register_async_method("heimdall_traceTransaction", |params, provider| async move {
+                let transaction_hash: String = params.one()?;
+                let transaction_hash = H256::from_str(&transaction_hash)
+                    .map_err(|_| RpcError::Custom("invalid transaction hash".to_string()))?;
+
+                provider
+                    .trace_transaction(transaction_hash)
+                    .await
+                    .map_err(|err| RpcError::Custom(err.to_string()))
+            })
+            .expect("failed to register heimdall_traceTransaction");
+
+        module
+            .register_async_method("heimdall_decodeCalldata", |params, provider| async move {
+                let target: String = params.one()?;
+
+                // reuse the persistent provider directly, rather than
+                // handing `decode` an rpc_url and letting it open (and
+                // reconnect) its own `Provider<Http>` on every call.
+                decode_calldata_with_provider(target, provider, false)
+                    .await
+                    .map_err(|err| RpcError::Custom(err.to_string()))
+            })
+            .expect("failed to register heimdall_decodeCalldata");
+
+        module
+            .register_async_method("heimdall_disassemble", |params, provider| async move {
+                let target: String = params.one()?;
+
+                heimdall_common::ether::evm::disassemble::disassemble_bytecode(&provider, &target)
+                    .await
+                    .map_err(|err| RpcError::Custom(err.to_string()))
+            })
+            .expect("failed to register heimdall_disassemble");
+
+        let addr = format!("127.0.0.1:{}", args.port);
+
+        let server = match HttpServerBuilder::default().build(&addr).await {
+            Ok(server) => server,
+            Err(err) => {
+                logger.error(&format!("failed to bind JSON-RPC server to '{}': {}", &addr, err));
+                std::process::exit(1)
+            }
+        };
+
+        logger.info(&format!("listening for JSON-RPC requests on '{}' .", &addr));
+
+        let handle = match server.start(module) {
+            Ok(handle) => handle,
+            Err(err) => {
+                logger.error(&format!("failed to start JSON-RPC server: {}", err));
+                std::process::exit(1)
+            }
+        };
+
+        handle.stopped().await;
+    });
+}
@@ -1,21 +1,31 @@
-use std::{time::Instant, str::FromStr};
+use std::{time::Instant, str::FromStr, collections::HashMap};
 use clap::{AppSettings, Parser};
 use ethers::{
     prelude::{Provider, Http, Middleware, Trace},
-    types::{H256},
+    types::{H256, Address, BlockNumber, NameOrAddress, Action, Res, CallType},
+    types::trace::{TraceFilter, TraceType, VMTrace},
+    utils::hex,
+};
+use heimdall_common::{
+    io::logging::Logger,
+    consts::TRANSACTION_HASH_REGEX,
+    ether::evm::opcodes::opcode,
+    ether::signatures::{score_signature, decode_calldata_with_signature, decode_return_data},
+    resources::selectors::{resolve_selectors, ResolvedFunction},
 };
-use heimdall_common::{io::logging::Logger, consts::TRANSACTION_HASH_REGEX};
 
 
 #[derive(Debug, Clone, Parser)]
 #[clap(about = "Trace the execution of an EVM transaction hash",
        after_help = "For more information, read the wiki: https://jbecker.dev/r/heimdall-rs/wiki",
-       global_setting = AppSettings::DeriveDisplayOrder, 
+       global_setting = AppSettings::DeriveDisplayOrder,
        override_usage = "heimdall trace <TRANSACTION_HASH> [OPTIONS]")]
 pub struct TraceArgs {
-    
-    /// The transaction hash to trace.
-    #[clap(required=true)]
+
+    /// The transaction hash to trace. Not required when filtering by block
+    /// range / address with --from-block, --to-block, --from-address, or
+    /// --to-address.
+    #[clap(required=false, default_value = "")]
     pub transaction_hash: String,
 
     /// Set the output verbosity level, 1 - 5.
@@ -30,17 +40,505 @@ pub struct TraceArgs {
     #[clap(long, short)]
     pub default: bool,
 
+    /// The block to start filtering from. Only used when transaction_hash is
+    /// not given. Accepts a block number or "earliest"/"latest"/"pending".
+    #[clap(long="from-block", default_value = "", hide_default_value = true)]
+    pub from_block: String,
+
+    /// The block to stop filtering at. Only used when transaction_hash is
+    /// not given. Accepts a block number or "earliest"/"latest"/"pending".
+    #[clap(long="to-block", default_value = "", hide_default_value = true)]
+    pub to_block: String,
+
+    /// A comma-separated list of addresses to filter calls originating from.
+    #[clap(long="from-address", default_value = "", hide_default_value = true)]
+    pub from_address: String,
+
+    /// A comma-separated list of addresses to filter calls directed to.
+    #[clap(long="to-address", default_value = "", hide_default_value = true)]
+    pub to_address: String,
+
+    /// Trace at the opcode level using `trace_replayTransaction`'s vmTrace,
+    /// rather than the default action-level call tree.
+    #[clap(long="vm-trace")]
+    pub vm_trace: bool,
+
+    /// Don't resolve function signatures; show raw calldata instead.
+    /// Resolving is enabled by default.
+    #[clap(long="no-resolve")]
+    pub no_resolve: bool,
+
+    /// Emit collapsed-stack folded output of gas usage across the call tree,
+    /// suitable for piping into a flamegraph renderer, instead of the tree.
+    #[clap(long="flamegraph")]
+    pub flamegraph: bool,
+
+}
+
+/// A node in the call tree, built from a trace's `trace_address` and the
+/// subset of its siblings/children that share the same parent address.
+struct TraceNode<'a> {
+    trace: &'a Trace,
+    children: Vec<TraceNode<'a>>,
+}
+
+/// Reconstruct the call tree(s) from a flat list of traces.
+///
+/// `trace_filter` (unlike `trace_transaction`) can return traces from
+/// multiple transactions in one `Vec<Trace>`, and every transaction's root
+/// shares the same empty `trace_address`, every depth-1 child shares `[0]`,
+/// `[1]`, … and so on. Partition by transaction first so that reconstructing
+/// one transaction's tree never picks up another transaction's children,
+/// then build each transaction's tree independently.
+fn build_tree(traces: &[Trace]) -> Vec<TraceNode> {
+    let mut order: Vec<(H256, Option<usize>)> = Vec::new();
+    let mut by_transaction: HashMap<(H256, Option<usize>), Vec<&Trace>> = HashMap::new();
+
+    for trace in traces {
+        let key = (trace.block_hash, trace.transaction_position);
+
+        by_transaction.entry(key).or_insert_with(|| {
+            order.push(key);
+            Vec::new()
+        }).push(trace);
+    }
+
+    order.into_iter().flat_map(|key| build_tree_for_transaction(&by_transaction[&key])).collect()
+}
+
+/// Reconstruct the call tree for a single transaction's traces.
+///
+/// The root is the trace whose `trace_address` is empty. Every other trace's
+/// parent is the trace whose `trace_address` is this trace's `trace_address`
+/// with the last element removed. Siblings are sorted by their last address
+/// index, which matches call order within their parent.
+fn build_tree_for_transaction<'a>(traces: &[&'a Trace]) -> Vec<TraceNode<'a>> {
+    let mut children_of: HashMap<Vec<usize>, Vec<&Trace>> = HashMap::new();
+
+    for trace in traces {
+        let parent_address = if trace.trace_address.is_empty() {
+            continue;
+        } else {
+            trace.trace_address[..trace.trace_address.len() - 1].to_vec()
+        };
+
+        children_of.entry(parent_address).or_insert_with(Vec::new).push(*trace);
+    }
+
+    for siblings in children_of.values_mut() {
+        siblings.sort_by_key(|trace| *trace.trace_address.last().unwrap_or(&0));
+    }
+
+    fn build_node<'a>(trace: &'a Trace, children_of: &HashMap<Vec<usize>, Vec<&'a Trace>>) -> TraceNode<'a> {
+        let children = children_of
+            .get(&trace.trace_address)
+            .map(|children| children.iter().map(|child| build_node(child, children_of)).collect())
+            .unwrap_or_default();
+
+        TraceNode { trace, children }
+    }
+
+    let mut roots: Vec<&Trace> = traces.iter().filter(|trace| trace.trace_address.is_empty()).copied().collect();
+    roots.sort_by_key(|trace| trace.transaction_position.unwrap_or(0));
+
+    roots.into_iter().map(|root| build_node(root, &children_of)).collect()
+}
+
+/// Truncate calldata/returndata to a readable length for tree output.
+fn truncate_bytes(bytes: &[u8], max_len: usize) -> String {
+    let hex = format!("0x{}", hex::encode(bytes));
+
+    if hex.len() > max_len {
+        format!("{}...", &hex[..max_len])
+    } else {
+        hex
+    }
+}
+
+/// Resolve a call's 4-byte selector into a human-readable function call,
+/// decoding arguments when the resolved signature can be parsed. Falls back
+/// to the raw truncated calldata when the selector isn't resolved. Argument
+/// decoding itself is `decode`'s job, not reimplemented here.
+fn describe_calldata(input: &[u8], resolved: Option<&HashMap<String, String>>) -> String {
+    let resolved = match resolved {
+        Some(resolved) => resolved,
+        None => return format!("input: {}", truncate_bytes(input, 42)),
+    };
+
+    if input.len() < 4 {
+        return format!("input: {}", truncate_bytes(input, 42));
+    }
+
+    let selector = hex::encode(&input[..4]);
+
+    let signature = match resolved.get(&selector) {
+        Some(signature) => signature,
+        None => return format!("input: {}", truncate_bytes(input, 42)),
+    };
+
+    match decode_calldata_with_signature(signature, input) {
+        Some(args) => format!("call: {}({})", signature, args),
+        None => format!("call: {}", signature),
+    }
+}
+
+/// Describe the action portion of a trace (call type, from/to, value, input).
+fn describe_action(action: &Action, resolved: Option<&HashMap<String, String>>) -> String {
+    match action {
+        Action::Call(call) => {
+            let call_type = match call.call_type {
+                CallType::Call => "CALL",
+                CallType::CallCode => "CALLCODE",
+                CallType::DelegateCall => "DELEGATECALL",
+                CallType::StaticCall => "STATICCALL",
+                CallType::None => "NONE",
+            };
+
+            format!(
+                "{} {:?} → {:?} | value: {} | {}",
+                call_type, call.from, call.to, call.value, describe_calldata(&call.input, resolved)
+            )
+        }
+        Action::Create(create) => {
+            format!(
+                "CREATE {:?} | value: {} | init: {}",
+                create.from, create.value, truncate_bytes(&create.init, 42)
+            )
+        }
+        Action::Suicide(suicide) => {
+            format!(
+                "SELFDESTRUCT {:?} → {:?} | balance: {}",
+                suicide.address, suicide.refund_address, suicide.balance
+            )
+        }
+        Action::Reward(reward) => {
+            format!("REWARD {:?} | value: {}", reward.author, reward.value)
+        }
+    }
+}
+
+/// Describe the result portion of a trace (gas used, output/created address).
+/// When the call's signature was resolved (or the output is a standard
+/// Solidity revert/panic), the output is ABI-decoded instead of shown raw.
+fn describe_result(result: &Option<Res>, action: &Action, resolved: Option<&HashMap<String, String>>) -> String {
+    match result {
+        Some(Res::Call(call)) => {
+            let signature = match action {
+                Action::Call(call) if call.input.len() >= 4 => {
+                    resolved.and_then(|resolved| resolved.get(&hex::encode(&call.input[..4])))
+                }
+                _ => None,
+            };
+
+            match decode_return_data(signature.map(|signature| signature.as_str()), &call.output) {
+                Some(decoded) => format!("gas: {} | returns: {}", call.gas_used, decoded),
+                None => format!("gas: {} | output: {}", call.gas_used, truncate_bytes(&call.output, 42)),
+            }
+        }
+        Some(Res::Create(create)) => {
+            format!("gas: {} | address: {:?}", create.gas_used, create.address)
+        }
+        Some(Res::None) | None => "".to_string(),
+    }
+}
+
+/// Walk the call tree depth-first, printing one line per node with
+/// box-drawing connectors to show nesting.
+fn print_tree(logger: &Logger, node: &TraceNode, prefix: &str, is_last: bool, is_root: bool, resolved: Option<&HashMap<String, String>>) {
+    let connector = if is_root {
+        ""
+    } else if is_last {
+        "└─ "
+    } else {
+        "├─ "
+    };
+
+    let description = describe_action(&node.trace.action, resolved);
+    let result = describe_result(&node.trace.result, &node.trace.action, resolved);
+
+    if result.is_empty() {
+        logger.info(&format!("{}{}{}", prefix, connector, description));
+    } else {
+        logger.info(&format!("{}{}{} | {}", prefix, connector, description, result));
+    }
+
+    if let Some(error) = &node.trace.error {
+        let message = decode_error_reason(error).unwrap_or_else(|| error.clone());
+        logger.info(&format!("{}{}   reverted: {}", prefix, if is_root { "" } else { "   " }, message));
+    }
+
+    let child_prefix = if is_root {
+        prefix.to_string()
+    } else if is_last {
+        format!("{}   ", prefix)
+    } else {
+        format!("{}│  ", prefix)
+    };
+
+    for (i, child) in node.children.iter().enumerate() {
+        print_tree(logger, child, &child_prefix, i == node.children.len() - 1, false, resolved);
+    }
+}
+
+/// Of the candidate signatures the selector database returned for a single
+/// selector, defer to `decode`'s own heuristic scorer to rank them, rather
+/// than re-deriving a disambiguation heuristic here.
+fn select_best_candidate(candidates: Vec<ResolvedFunction>) -> Option<ResolvedFunction> {
+    candidates.into_iter().max_by_key(|candidate| score_signature(&candidate.name))
+}
+
+/// Collect the unique 4-byte selectors called within a trace tree and
+/// resolve them to function signatures via the same selector database and
+/// heuristic scorer `decode` uses, so both commands share one resolution
+/// path instead of trace maintaining its own.
+async fn resolve_signatures(traces: &[Trace], logger: &Logger) -> HashMap<String, String> {
+    let selectors: Vec<String> = traces
+        .iter()
+        .filter_map(|trace| match &trace.action {
+            Action::Call(call) if call.input.len() >= 4 => Some(hex::encode(&call.input[..4])),
+            _ => None,
+        })
+        .collect::<std::collections::HashSet<_>>()
+        .into_iter()
+        .collect();
+
+    if selectors.is_empty() {
+        return HashMap::new();
+    }
+
+    match resolve_selectors(selectors).await {
+        Ok(resolved) => resolved
+            .into_iter()
+            .filter_map(|(selector, functions)| {
+                let best = select_best_candidate(functions?)?;
+                Some((selector, best.name))
+            })
+            .collect(),
+        Err(err) => {
+            logger.error(&format!("failed to resolve signatures: {}", err));
+            HashMap::new()
+        }
+    }
+}
+
+/// Decode a Parity-style trace's `error` field, which (unlike a plain
+/// successful call's `result.output`) is where a revert's payload actually
+/// shows up — `trace_transaction`/`trace_filter` never populate `result` for
+/// a reverted call. Most providers put a plain message here ("Reverted",
+/// "out of gas"), which isn't decodable and is returned as-is by the caller;
+/// when it's hex-encoded revert data, decode it the same way `decode` does.
+fn decode_error_reason(error: &str) -> Option<String> {
+    let bytes = hex::decode(error.strip_prefix("0x").unwrap_or(error)).ok()?;
+    decode_return_data(None, &bytes)
+}
+
+/// Label a flamegraph frame as `<address>_<signature>`, falling back to the
+/// raw selector or action kind when no signature was resolved.
+fn frame_label(trace: &Trace, resolved: Option<&HashMap<String, String>>) -> String {
+    match &trace.action {
+        Action::Call(call) => {
+            let signature = if call.input.len() >= 4 {
+                let selector = hex::encode(&call.input[..4]);
+                resolved.and_then(|resolved| resolved.get(&selector)).cloned().unwrap_or(selector)
+            } else {
+                "fallback".to_string()
+            };
+
+            format!("{:?}_{}", call.to, signature)
+        }
+        Action::Create(create) => format!("{:?}_CREATE", create.from),
+        Action::Suicide(suicide) => format!("{:?}_SELFDESTRUCT", suicide.address),
+        Action::Reward(reward) => format!("{:?}_REWARD", reward.author),
+    }
+}
+
+/// The gas consumed by a trace, as reported in its result.
+fn gas_used(result: &Option<Res>) -> u64 {
+    match result {
+        Some(Res::Call(call)) => call.gas_used.as_u64(),
+        Some(Res::Create(create)) => create.gas_used.as_u64(),
+        Some(Res::None) | None => 0,
+    }
+}
+
+/// Walk the call tree emitting one collapsed-stack line per frame, weighted
+/// by that frame's self-gas (its own `gas_used` minus the sum of its direct
+/// children's `gas_used`). Frames that spent no gas themselves are omitted.
+fn collect_flamegraph_lines(node: &TraceNode, stack: &str, resolved: Option<&HashMap<String, String>>, lines: &mut Vec<String>) {
+    let path = format!("{};{}", stack, frame_label(node.trace, resolved));
+
+    let children_gas: u64 = node.children.iter().map(|child| gas_used(&child.trace.result)).sum();
+    let self_gas = gas_used(&node.trace.result).saturating_sub(children_gas);
+
+    if self_gas > 0 {
+        lines.push(format!("{} {}", path, self_gas));
+    }
+
+    for child in &node.children {
+        collect_flamegraph_lines(child, &path, resolved, lines);
+    }
+}
+
+/// Parse a `--from-block`/`--to-block` value into an ethers `BlockNumber`,
+/// accepting either a decimal block number or the "earliest"/"latest"/
+/// "pending" tags. An unset value falls back to `default`, so a bare
+/// `--from-address`/`--to-address` filter with no block flags still covers
+/// the whole chain (`fromBlock=earliest, toBlock=latest`) rather than
+/// collapsing to a single-block query.
+fn parse_block_number(value: &str, default: BlockNumber) -> BlockNumber {
+    match value {
+        "" => default,
+        "earliest" => BlockNumber::Earliest,
+        "latest" => BlockNumber::Latest,
+        "pending" => BlockNumber::Pending,
+        number => match u64::from_str(number) {
+            Ok(number) => BlockNumber::Number(number.into()),
+            Err(_) => default,
+        },
+    }
+}
+
+/// Parse a comma-separated `--from-address`/`--to-address` value into a list
+/// of addresses for `trace_filter`, skipping anything that fails to parse.
+fn parse_address_list(value: &str, logger: &Logger) -> Vec<NameOrAddress> {
+    value
+        .split(',')
+        .map(|address| address.trim())
+        .filter(|address| !address.is_empty())
+        .filter_map(|address| match Address::from_str(address) {
+            Ok(address) => Some(NameOrAddress::Address(address)),
+            Err(_) => {
+                logger.error(&format!("failed to parse address '{}' .", address));
+                None
+            }
+        })
+        .collect()
+}
+
+/// Walk a `VMTrace` (and its nested call `sub`-traces) depth-first, printing
+/// one line per executed opcode in program order. `code` is the bytecode the
+/// trace was recorded against, used to resolve each program counter to its
+/// opcode mnemonic, the same table `disassemble` uses.
+fn print_vm_trace(logger: &Logger, vm_trace: &VMTrace, depth: usize) {
+    let indent = "  ".repeat(depth);
+
+    for operation in &vm_trace.ops {
+        let op = opcode(*vm_trace.code.get(operation.pc).unwrap_or(&0));
+
+        let gas_used = operation.ex.as_ref().map(|ex| ex.used).unwrap_or(0);
+
+        logger.info(&format!(
+            "{}{:>6}  {:<16} cost: {:<6} gas_left: {}",
+            indent, operation.pc, op.name, operation.cost, gas_used
+        ));
+
+        if let Some(ex) = &operation.ex {
+            if let Some(store) = &ex.store {
+                logger.info(&format!("{}        store [{:?}] = {:?}", indent, store.key, store.val));
+            }
+
+            if let Some(mem) = &ex.mem {
+                logger.info(&format!("{}        memory[{}..] = 0x{}", indent, mem.off, hex::encode(&mem.data)));
+            }
+        }
+
+        // a `sub` trace is the vmTrace of a nested CALL/CREATE made by this
+        // opcode; render it immediately under its caller so the listing
+        // stays in execution order.
+        if let Some(operation) = &operation.sub {
+            print_vm_trace(logger, operation, depth + 1);
+        }
+    }
 }
 
+#[allow(deprecated)]
+fn run_vm_trace(args: &TraceArgs, logger: &Logger, now: Instant) {
+
+    // create new runtime block
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    let vm_trace = rt.block_on(async {
+
+        // make sure the RPC provider isn't empty
+        if &args.rpc_url.len() <= &0 {
+            logger.error("vm-tracing a transaction requires an RPC provider. Use `heimdall trace --help` for more information.");
+            std::process::exit(1);
+        }
+
+        // create new provider
+        let provider = match Provider::<Http>::try_from(&args.rpc_url) {
+            Ok(provider) => provider,
+            Err(_) => {
+                logger.error(&format!("failed to connect to RPC provider '{}' .", &args.rpc_url).to_string());
+                std::process::exit(1)
+            }
+        };
+
+        // safely unwrap the transaction hash
+        let transaction_hash = match H256::from_str(&args.transaction_hash) {
+            Ok(transaction_hash) => transaction_hash,
+            Err(_) => {
+                logger.error(&format!("failed to parse transaction hash '{}' .", &args.transaction_hash));
+                std::process::exit(1)
+            }
+        };
+
+        // replay the transaction requesting only the vmTrace, which gives
+        // us a step-by-step listing of every opcode executed.
+        match provider.trace_replay_transaction(transaction_hash, vec![TraceType::VmTrace]).await {
+            Ok(block_trace) => match block_trace.vm_trace {
+                Some(vm_trace) => vm_trace,
+                None => {
+                    logger.error(&format!("no vmTrace was returned for '{}' .", &args.transaction_hash));
+                    std::process::exit(1)
+                }
+            },
+            Err(err) => {
+                println!("{:#?}", err);
+                logger.error(&format!("failed to replay '{}' . does your provider support 'trace_replayTransaction' ?", &args.transaction_hash));
+                std::process::exit(1)
+            }
+        }
+
+    });
+
+    print_vm_trace(logger, &vm_trace, 0);
+
+    let elapsed = now.elapsed();
+    logger.debug(&format!("vm trace completed in {} ms.", elapsed.as_millis()).to_string());
+}
 
 #[allow(deprecated)]
 pub fn trace(args: TraceArgs) {
     let now = Instant::now();
     let (logger, mut trace)= Logger::new(args.verbose.log_level().unwrap().as_str());
 
+    // opcode-level VM tracing uses a completely different RPC call and data
+    // model than the action-level call tree, so it's handled as its own path.
+    if args.vm_trace {
+        if !TRANSACTION_HASH_REGEX.is_match(&args.transaction_hash) {
+            logger.error(&format!("invalid transaction hash '{}' .", &args.transaction_hash));
+            std::process::exit(1)
+        }
+
+        run_vm_trace(&args, &logger, now);
+        return;
+    }
+
+    // a filter is in use if any of the block-range/address flags are set
+    let using_filter = !args.from_block.is_empty()
+        || !args.to_block.is_empty()
+        || !args.from_address.is_empty()
+        || !args.to_address.is_empty();
+
     let traces: Vec<Trace>;
 
-    // determine whether or not the target is a transaction hash
+    // determine whether or not the target is a transaction hash, or whether
+    // we should fall back to filtering by block range / address via
+    // `trace_filter`.
     if TRANSACTION_HASH_REGEX.is_match(&args.transaction_hash) {
 
         // create new runtime block
@@ -48,7 +546,7 @@ pub fn trace(args: TraceArgs) {
             .enable_all()
             .build()
             .unwrap();
-        
+
         // Fetch the raw traces from the RPC provider.
         traces = rt.block_on(async {
 
@@ -85,7 +583,58 @@ pub fn trace(args: TraceArgs) {
                     std::process::exit(1)
                 }
             }
-            
+
+        });
+    }
+    else if using_filter {
+
+        // create new runtime block
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+
+        // Fetch every trace matching the block range / address filter from
+        // the RPC provider, mirroring OpenEthereum's `trace_filter`.
+        traces = rt.block_on(async {
+
+            // make sure the RPC provider isn't empty
+            if &args.rpc_url.len() <= &0 {
+                logger.error("filtering traces requires an RPC provider. Use `heimdall trace --help` for more information.");
+                std::process::exit(1);
+            }
+
+            // create new provider
+            let provider = match Provider::<Http>::try_from(&args.rpc_url) {
+                Ok(provider) => provider,
+                Err(_) => {
+                    logger.error(&format!("failed to connect to RPC provider '{}' .", &args.rpc_url).to_string());
+                    std::process::exit(1)
+                }
+            };
+
+            let mut filter = TraceFilter::default();
+            filter.from_block = Some(parse_block_number(&args.from_block, BlockNumber::Earliest));
+            filter.to_block = Some(parse_block_number(&args.to_block, BlockNumber::Latest));
+
+            if !args.from_address.is_empty() {
+                filter.from_address = Some(parse_address_list(&args.from_address, &logger));
+            }
+
+            if !args.to_address.is_empty() {
+                filter.to_address = Some(parse_address_list(&args.to_address, &logger));
+            }
+
+            // fetch the matching traces from the node
+            match provider.trace_filter(filter).await {
+                Ok(traces) => traces,
+                Err(err) => {
+                    println!("{:#?}", err);
+                    logger.error("failed to fetch traces for the given filter. does your provider support 'trace_filter' ?");
+                    std::process::exit(1)
+                }
+            }
+
         });
     }
     else {
@@ -93,11 +642,164 @@ pub fn trace(args: TraceArgs) {
         std::process::exit(1)
     }
 
-    for trace in traces {
-        println!("{:#?}", trace);
+    // resolve call selectors to function signatures unless the user opted
+    // out, so the tree reads as `transfer(address,uint256)(...)` instead of
+    // raw calldata.
+    let resolved = if args.no_resolve {
+        None
+    } else {
+        let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        Some(rt.block_on(resolve_signatures(&traces, &logger)))
+    };
+
+    // reconstruct the call tree from the flat list of traces and render it,
+    // either as a folded-stack gas profile or the default depth-first tree.
+    if args.flamegraph {
+        let mut lines = Vec::new();
+
+        for root in build_tree(&traces) {
+            collect_flamegraph_lines(&root, "root", resolved.as_ref(), &mut lines);
+        }
+
+        for line in lines {
+            println!("{}", line);
+        }
+    } else {
+        for root in build_tree(&traces) {
+            print_tree(&logger, &root, "", true, true, resolved.as_ref());
+        }
     }
 
     let elapsed = now.elapsed();
     logger.debug(&format!("disassembly completed in {} ms.", elapsed.as_millis()).to_string());
 
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers::types::{trace::{Call, CallResult, ActionType}, U256};
+
+    /// Build a minimal `Trace` with the fields the tree/flamegraph logic
+    /// actually reads; everything else is zeroed out.
+    fn make_trace(trace_address: Vec<usize>, transaction_position: Option<usize>, block_hash: H256, gas_used: u64) -> Trace {
+        Trace {
+            action: Action::Call(Call {
+                from: Address::zero(),
+                to: Address::zero(),
+                value: U256::zero(),
+                gas: U256::zero(),
+                input: vec![].into(),
+                call_type: CallType::Call,
+            }),
+            result: Some(Res::Call(CallResult { gas_used: gas_used.into(), output: vec![].into() })),
+            trace_address,
+            subtraces: 0,
+            transaction_position,
+            transaction_hash: None,
+            block_number: 0,
+            block_hash,
+            action_type: ActionType::Call,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn build_tree_for_transaction_nests_children_under_their_parent() {
+        let root = make_trace(vec![], Some(0), H256::zero(), 0);
+        let child_0 = make_trace(vec![0], Some(0), H256::zero(), 0);
+        let child_1 = make_trace(vec![1], Some(0), H256::zero(), 0);
+        let grandchild = make_trace(vec![0, 0], Some(0), H256::zero(), 0);
+
+        // shuffled on purpose: reconstruction must not depend on input order
+        let traces = vec![&grandchild, &child_1, &root, &child_0];
+        let tree = build_tree_for_transaction(&traces);
+
+        assert_eq!(tree.len(), 1);
+        let root_node = &tree[0];
+        assert_eq!(root_node.children.len(), 2);
+
+        // siblings are ordered by their trailing trace_address index
+        assert_eq!(root_node.children[0].trace.trace_address, vec![0]);
+        assert_eq!(root_node.children[1].trace.trace_address, vec![1]);
+
+        assert_eq!(root_node.children[0].children.len(), 1);
+        assert_eq!(root_node.children[0].children[0].trace.trace_address, vec![0, 0]);
+        assert_eq!(root_node.children[1].children.len(), 0);
+    }
+
+    #[test]
+    fn build_tree_partitions_by_transaction_before_reconstructing() {
+        let first_block = H256::from_low_u64_be(1);
+        let second_block = H256::from_low_u64_be(2);
+
+        // two transactions whose traces share the same trace_address shape;
+        // if they were merged before reconstruction, tx 1's root would pick
+        // up tx 2's depth-1 trace as a child (or vice versa).
+        let tx1_root = make_trace(vec![], Some(0), first_block, 0);
+        let tx1_child = make_trace(vec![0], Some(0), first_block, 0);
+        let tx2_root = make_trace(vec![], Some(1), second_block, 0);
+        let tx2_child = make_trace(vec![0], Some(1), second_block, 0);
+
+        let traces = vec![tx2_child, tx1_root, tx2_root, tx1_child];
+        let tree = build_tree(&traces);
+
+        assert_eq!(tree.len(), 2);
+        for root_node in &tree {
+            assert_eq!(root_node.children.len(), 1);
+        }
+    }
+
+    #[test]
+    fn collect_flamegraph_lines_subtracts_children_gas_from_self_gas() {
+        let parent_trace = make_trace(vec![], Some(0), H256::zero(), 100);
+        let child_trace = make_trace(vec![0], Some(0), H256::zero(), 40);
+
+        let tree = TraceNode {
+            trace: &parent_trace,
+            children: vec![TraceNode { trace: &child_trace, children: vec![] }],
+        };
+
+        let mut lines = Vec::new();
+        collect_flamegraph_lines(&tree, "root", None, &mut lines);
+
+        // parent's self gas is 100 - 40 = 60, child's self gas is 40 - 0 = 40
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" 60"));
+        assert!(lines[1].ends_with(" 40"));
+    }
+
+    #[test]
+    fn collect_flamegraph_lines_omits_frames_with_no_self_gas() {
+        // a pure dispatcher whose entire gas budget was spent in its one
+        // child should not show up as its own flamegraph frame.
+        let parent_trace = make_trace(vec![], Some(0), H256::zero(), 40);
+        let child_trace = make_trace(vec![0], Some(0), H256::zero(), 40);
+
+        let tree = TraceNode {
+            trace: &parent_trace,
+            children: vec![TraceNode { trace: &child_trace, children: vec![] }],
+        };
+
+        let mut lines = Vec::new();
+        collect_flamegraph_lines(&tree, "root", None, &mut lines);
+
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with(" 40"));
+    }
+
+    #[test]
+    fn parse_block_number_falls_back_to_the_given_default_when_unset() {
+        assert_eq!(parse_block_number("", BlockNumber::Earliest), BlockNumber::Earliest);
+        assert_eq!(parse_block_number("", BlockNumber::Latest), BlockNumber::Latest);
+    }
+
+    #[test]
+    fn parse_block_number_parses_tags_and_numbers() {
+        assert_eq!(parse_block_number("earliest", BlockNumber::Latest), BlockNumber::Earliest);
+        assert_eq!(parse_block_number("latest", BlockNumber::Earliest), BlockNumber::Latest);
+        assert_eq!(parse_block_number("pending", BlockNumber::Earliest), BlockNumber::Pending);
+        assert_eq!(parse_block_number("42", BlockNumber::Earliest), BlockNumber::Number(42.into()));
+        assert_eq!(parse_block_number("not-a-number", BlockNumber::Latest), BlockNumber::Latest);
+    }
+}